@@ -0,0 +1,217 @@
+//! A compatibility layer standing in for this crate's `tokio_core`
+//! dependency.
+//!
+//! This crate predates the `Core`/`Handle`/`Remote` naming tokio-core
+//! eventually settled on: everywhere else in this crate expects the earlier
+//! `Loop`/`LoopHandle`/`LoopPin` names, with `tcp_listen`/`udp_bind`/
+//! `timeout` all returning futures instead of binding synchronously. Rather
+//! than rewrite the rest of the crate against the newer API, this module
+//! gives those old names a real implementation on top of `tokio-core-real`
+//! so the crate actually builds and its tests actually run.
+//!
+//! This is strictly single-threaded: unlike the real `Remote`, `LoopHandle`
+//! here is never sent to another thread before the loop it belongs to has
+//! started running, so there's no need for the channel-based handoff
+//! `Remote::spawn` uses to cross into the loop's thread.
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use futures::{Async, Future, IntoFuture, Poll};
+use tokio_core_real::net as real_net;
+use tokio_core_real::reactor as real_reactor;
+
+pub mod io {
+    pub type IoFuture<T> = Box<::futures::Future<Item = T, Error = ::std::io::Error>>;
+}
+
+fn boxed_ok<T: 'static>(value: T) -> io::IoFuture<T> {
+    Box::new(::futures::finished(value))
+}
+
+fn boxed_err<T: 'static>(err: ::std::io::Error) -> io::IoFuture<T> {
+    Box::new(::futures::failed(err))
+}
+
+/// An event loop, in this crate's pre-`Core` naming.
+///
+/// Nothing inside this crate constructs a `Loop` itself -- that's always the
+/// caller's job, same as `Core` in the real tokio-core -- so it's only ever
+/// used from outside (our own test suite included).
+#[allow(dead_code)]
+pub struct Loop {
+    core: real_reactor::Core,
+}
+
+#[allow(dead_code)]
+impl Loop {
+    pub fn new() -> ::std::io::Result<Loop> {
+        Ok(Loop { core: try!(real_reactor::Core::new()) })
+    }
+
+    pub fn handle(&self) -> LoopHandle {
+        LoopHandle { inner: self.core.handle() }
+    }
+
+    pub fn run<F: Future>(&mut self, f: F) -> Result<F::Item, F::Error> {
+        self.core.run(f)
+    }
+}
+
+/// A cloneable handle for binding listeners/sockets and spawning tasks onto
+/// the loop that created it.
+#[derive(Clone)]
+pub struct LoopHandle {
+    inner: real_reactor::Handle,
+}
+
+/// A handle for spawning further tasks from within an already-spawned task.
+/// Identical to `LoopHandle` in this compatibility layer; the real API's
+/// split only matters once spawning crosses threads, which this crate never
+/// does.
+#[derive(Clone)]
+pub struct LoopPin {
+    inner: real_reactor::Handle,
+}
+
+impl LoopHandle {
+    pub fn tcp_listen(&self, addr: &SocketAddr) -> io::IoFuture<TcpListener> {
+        match real_net::TcpListener::bind(addr, &self.inner) {
+            Ok(listener) => boxed_ok(TcpListener { inner: listener }),
+            Err(e) => boxed_err(e),
+        }
+    }
+
+    pub fn udp_bind(&self, addr: &SocketAddr) -> io::IoFuture<UdpSocket> {
+        match real_net::UdpSocket::bind(addr, &self.inner) {
+            Ok(socket) => boxed_ok(UdpSocket { inner: socket }),
+            Err(e) => boxed_err(e),
+        }
+    }
+
+    pub fn timeout(&self, dur: Duration) -> io::IoFuture<Timeout> {
+        match real_reactor::Timeout::new(dur, &self.inner) {
+            Ok(timeout) => boxed_ok(Timeout { inner: timeout }),
+            Err(e) => boxed_err(e),
+        }
+    }
+
+    /// Spawn a task onto the loop, handing the closure a `LoopPin` to spawn
+    /// further tasks with.
+    pub fn spawn<F, R>(&self, f: F)
+        where F: FnOnce(LoopPin) -> R + 'static,
+              R: IntoFuture<Item = (), Error = ::std::io::Error>,
+              R::Future: 'static,
+    {
+        let pin = LoopPin { inner: self.inner.clone() };
+        self.inner.spawn(f(pin).into_future().map_err(|_| ()));
+    }
+}
+
+impl LoopPin {
+    pub fn spawn<F>(&self, f: F)
+        where F: Future<Item = (), Error = ::std::io::Error> + 'static,
+    {
+        self.inner.spawn(f.map_err(|_| ()));
+    }
+}
+
+/// A bound TCP listener, returned by `LoopHandle::tcp_listen`.
+pub struct TcpListener {
+    inner: real_net::TcpListener,
+}
+
+impl TcpListener {
+    pub fn local_addr(&self) -> ::std::io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub fn incoming(self) -> Box<::futures::Stream<Item = (TcpStream, SocketAddr), Error = ::std::io::Error>> {
+        use futures::Stream;
+        Box::new(self.inner.incoming().map(|(stream, addr)| (TcpStream { inner: stream }, addr)))
+    }
+}
+
+/// A connected (or accepted) TCP stream.
+pub struct TcpStream {
+    inner: real_net::TcpStream,
+}
+
+impl TcpStream {
+    pub fn local_addr(&self) -> ::std::io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Test whether the socket is readable without blocking, registering
+    /// this task for a wakeup if not.
+    pub fn poll_read(&self) -> Async<()> {
+        self.inner.poll_read()
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// A bound UDP socket.
+pub struct UdpSocket {
+    inner: real_net::UdpSocket,
+}
+
+impl UdpSocket {
+    pub fn local_addr(&self) -> ::std::io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub fn poll_read(&self) -> Async<()> {
+        self.inner.poll_read()
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> ::std::io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+
+    pub fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> ::std::io::Result<usize> {
+        self.inner.send_to(buf, addr)
+    }
+}
+
+impl AsRawFd for UdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// A future that resolves once a duration has elapsed.
+pub struct Timeout {
+    inner: real_reactor::Timeout,
+}
+
+impl Future for Timeout {
+    type Item = ();
+    type Error = ::std::io::Error;
+
+    fn poll(&mut self) -> Poll<(), ::std::io::Error> {
+        self.inner.poll()
+    }
+}
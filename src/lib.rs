@@ -0,0 +1,27 @@
+//! A generic Tokio-based server toolkit.
+
+// This crate is written against an early, pre-`?`/pre-`dyn` tokio-core API
+// (see `compat`), so it leans on `try!`, bare trait objects, and the
+// pre-`notify`-rename futures 0.1 task API throughout; allow the lints a
+// newer edition would otherwise raise for those deliberate choices.
+#![allow(deprecated, bare_trait_objects)]
+// Same era, same reasoning: explicit `field: field` predates field-init
+// shorthand being idiomatic, `io::Error::other` didn't exist yet, and an
+// explicit borrow reads clearer than relying on auto-ref in code this old.
+#![allow(clippy::redundant_field_names, clippy::needless_borrow, clippy::io_other_error)]
+
+#[macro_use]
+extern crate log;
+extern crate futures;
+extern crate libc;
+extern crate take;
+extern crate tokio_core_real;
+
+pub mod compat;
+// Public so downstream crates implementing `server::NewTask` /
+// `server::NewDatagramTask` can name the `TcpStream`/`UdpSocket`/`Loop`
+// types those traits hand them, the same way they'd reach a real
+// `tokio_core` dependency.
+pub use compat as tokio_core;
+
+pub mod server;
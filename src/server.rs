@@ -1,17 +1,348 @@
 //! A generic Tokio TCP server implementation.
 
+use std::collections::HashMap;
 use std::io;
+use std::mem;
 use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::stream::Stream;
-use futures::Future;
+use futures::sync::{mpsc, oneshot};
+use futures::{task, Async, Future, Poll};
+use libc;
 use take::Take;
 use tokio_core::io::IoFuture;
-use tokio_core::{TcpStream, LoopHandle};
+use tokio_core::{TcpStream, UdpSocket, LoopHandle, LoopPin};
+
+/// Peek at up to `buf.len()` bytes sitting in `fd`'s receive queue without
+/// consuming them, via a raw `MSG_PEEK` read.
+///
+/// Neither `TcpStream` nor `UdpSocket` in this tokio-core generation expose a
+/// peeking read themselves (that only arrived in later releases), but the
+/// raw file descriptor behind them is always available, so we drop down to
+/// `libc::recv`/`recvfrom` directly rather than waiting on an API this crate
+/// doesn't have yet.
+fn peek_raw<S: AsRawFd>(socket: &S, buf: &mut [u8]) -> io::Result<usize> {
+    let ret = unsafe {
+        libc::recv(socket.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_PEEK)
+    };
+
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Like `peek_raw`, but also reports the sender's address, for connectionless
+/// sockets where `recv` alone can't tell callers who a datagram is from.
+fn peek_raw_from<S: AsRawFd>(socket: &S, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&storage) as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::recvfrom(socket.as_raw_fd(),
+                       buf.as_mut_ptr() as *mut libc::c_void,
+                       buf.len(),
+                       libc::MSG_PEEK,
+                       &mut storage as *mut _ as *mut libc::sockaddr,
+                       &mut len)
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let addr = try!(sockaddr_to_addr(&storage, len));
+    Ok((ret as usize, addr))
+}
+
+fn sockaddr_to_addr(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> io::Result<SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            debug_assert!(len as usize >= mem::size_of::<libc::sockaddr_in>());
+            let sa: &libc::sockaddr_in = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr));
+            let port = u16::from_be(sa.sin_port);
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+        }
+        libc::AF_INET6 => {
+            debug_assert!(len as usize >= mem::size_of::<libc::sockaddr_in6>());
+            let sa: &libc::sockaddr_in6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sa.sin6_addr.s6_addr);
+            let port = u16::from_be(sa.sin6_port);
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, sa.sin6_flowinfo, sa.sin6_scope_id)))
+        }
+        family => {
+            Err(io::Error::new(io::ErrorKind::Other,
+                                format!("unsupported address family {}", family)))
+        }
+    }
+}
+
+/// An error produced by a running server, either while accepting
+/// connections or while running an individual task.
+#[derive(Debug)]
+pub enum ServerError {
+    /// The accept loop itself errored; no further connections will be
+    /// accepted on this listener.
+    Accept(io::Error),
+    /// An individual task errored.
+    Task {
+        /// The error returned by the task.
+        error: io::Error,
+        /// The peer address of the connection, if known.
+        peer: Option<SocketAddr>,
+    },
+}
+
+/// Where a server's accept-loop and task errors go: a user-supplied
+/// handler, or the historical `error!` logging if none was configured.
+enum ErrorSink {
+    Log,
+    Handler(Mutex<Box<FnMut(ServerError) + Send>>),
+}
+
+impl ErrorSink {
+    fn new(handler: Option<Box<FnMut(ServerError) + Send>>) -> ErrorSink {
+        match handler {
+            Some(handler) => ErrorSink::Handler(Mutex::new(handler)),
+            None => ErrorSink::Log,
+        }
+    }
+
+    fn report(&self, err: ServerError) {
+        match *self {
+            ErrorSink::Log => match err {
+                ServerError::Accept(e) => error!("server error: {}", e),
+                ServerError::Task { error, peer: Some(addr) } => {
+                    error!("task error ({}): {}", addr, error)
+                }
+                ServerError::Task { error, peer: None } => error!("task error: {}", error),
+            },
+            ErrorSink::Handler(ref handler) => (&mut *handler.lock().unwrap())(err),
+        }
+    }
+}
 
 /// A handle to a running server.
 pub struct ServerHandle {
     local_addr: SocketAddr,
+    handle: LoopHandle,
+    stop: Mutex<Option<oneshot::Sender<()>>>,
+    tasks: Arc<TaskTracker>,
+}
+
+/// Shared bookkeeping for the tasks a server has spawned, so a `ServerHandle`
+/// can tell when every in-flight connection has finished and, if asked,
+/// force them to stop early, and so the accept loop can cap how many tasks
+/// run at once.
+struct TaskTracker {
+    count: AtomicUsize,
+    max: Option<usize>,
+    abort: AtomicBool,
+    drained: Mutex<Option<oneshot::Sender<()>>>,
+    parked: Mutex<Option<task::Task>>,
+    next_abortable_id: AtomicUsize,
+    // Task handles for every `Abortable` currently parked waiting on its
+    // inner task, so `force_abort` can wake them immediately instead of
+    // waiting for them to come back around to the reactor on their own.
+    aborting: Mutex<HashMap<usize, task::Task>>,
+}
+
+impl TaskTracker {
+    fn new(max: Option<usize>) -> TaskTracker {
+        TaskTracker {
+            count: AtomicUsize::new(0),
+            max: max,
+            abort: AtomicBool::new(false),
+            drained: Mutex::new(None),
+            parked: Mutex::new(None),
+            next_abortable_id: AtomicUsize::new(0),
+            aborting: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn register_abortable(&self) -> usize {
+        self.next_abortable_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn park_abortable(&self, id: usize, task: task::Task) {
+        self.aborting.lock().unwrap().insert(id, task);
+    }
+
+    fn unpark_abortable(&self, id: usize) {
+        self.aborting.lock().unwrap().remove(&id);
+    }
+
+    /// Tell every task guarded by `Abortable` to resolve on its next poll,
+    /// waking any that are currently parked waiting on their inner future
+    /// rather than leaving them to finish (or not) on their own.
+    fn force_abort(&self) {
+        self.abort.store(true, Ordering::SeqCst);
+        for (_, task) in self.aborting.lock().unwrap().drain() {
+            task.unpark();
+        }
+    }
+
+    fn task_started(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn task_finished(&self) {
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(tx) = self.drained.lock().unwrap().take() {
+                let _ = tx.send(());
+            }
+        }
+
+        // A slot just freed up; wake the accept loop if it's waiting on one.
+        if let Some(task) = self.parked.lock().unwrap().take() {
+            task.unpark();
+        }
+    }
+
+    /// Returns a future that resolves once a slot under `max` is available,
+    /// without spending a slot itself.
+    fn slot(self: &Arc<TaskTracker>) -> Slot {
+        Slot { tasks: self.clone() }
+    }
+
+    /// Returns a future that resolves once every task this tracker knows
+    /// about has finished.
+    fn drain(self: &Arc<TaskTracker>) -> IoFuture<()> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            return Box::new(futures::finished(()));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        *self.drained.lock().unwrap() = Some(tx);
+
+        // The last task may have finished between the check above and
+        // stashing the sender; make sure it still fires in that case.
+        if self.count.load(Ordering::SeqCst) == 0 {
+            if let Some(tx) = self.drained.lock().unwrap().take() {
+                let _ = tx.send(());
+            }
+        }
+
+        Box::new(rx.then(|_| Ok(())))
+    }
+}
+
+/// A future that resolves once a tracker has a free slot under its
+/// `max_connections` cap. Polling this (rather than busy-accepting and
+/// dropping connections past the cap) is what lets the accept loop stop
+/// pulling from `incoming()` and apply socket-level backpressure.
+struct Slot {
+    tasks: Arc<TaskTracker>,
+}
+
+impl Future for Slot {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let max = match self.tasks.max {
+            Some(max) => max,
+            None => return Ok(Async::Ready(())),
+        };
+
+        if self.tasks.count.load(Ordering::SeqCst) < max {
+            return Ok(Async::Ready(()));
+        }
+
+        *self.tasks.parked.lock().unwrap() = Some(task::park());
+
+        // A slot may have freed up between the check above and parking;
+        // make sure we don't miss it.
+        if self.tasks.count.load(Ordering::SeqCst) < max {
+            return Ok(Async::Ready(()));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// Wraps a task so it resolves immediately, dropping its connection, once
+/// the tracker it belongs to has been told to abort.
+struct Abortable<F> {
+    inner: F,
+    tasks: Arc<TaskTracker>,
+    id: usize,
+}
+
+impl<F> Future for Abortable<F>
+    where F: Future<Item=(), Error=io::Error>
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if self.tasks.abort.load(Ordering::SeqCst) {
+            self.tasks.unpark_abortable(self.id);
+            return Ok(Async::Ready(()));
+        }
+
+        match self.inner.poll() {
+            // Register ourselves so a later force_abort() can unpark us
+            // even though nothing about `inner`'s own wakeup sources knows
+            // to do so.
+            Ok(Async::NotReady) => {
+                self.tasks.park_abortable(self.id, task::park());
+                Ok(Async::NotReady)
+            }
+            other => {
+                self.tasks.unpark_abortable(self.id);
+                other
+            }
+        }
+    }
+}
+
+/// Wraps a future so it also resolves -- as an error, same as a failed peek
+/// -- once the tracker it belongs to has been told to abort.
+///
+/// `Peek` runs before a connection has a dispatched `Task` for `Abortable`
+/// to guard, but `task_started()` is already called for it by the time it
+/// starts polling (so the accept loop's slot/count accounting covers the
+/// peek phase too), so `shutdown_timeout`'s post-abort drain would hang
+/// forever on a connection that never sends enough bytes to finish peeking
+/// without this: it only wakes on real socket readiness, never on abort.
+struct AbortablePeek<F> {
+    inner: F,
+    tasks: Arc<TaskTracker>,
+    id: usize,
+}
+
+impl<F> Future for AbortablePeek<F>
+    where F: Future<Error=io::Error>
+{
+    type Item = F::Item;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, io::Error> {
+        if self.tasks.abort.load(Ordering::SeqCst) {
+            self.tasks.unpark_abortable(self.id);
+            return Err(io::Error::new(io::ErrorKind::Other, "server is shutting down"));
+        }
+
+        match self.inner.poll() {
+            Ok(Async::NotReady) => {
+                self.tasks.park_abortable(self.id, task::park());
+                Ok(Async::NotReady)
+            }
+            other => {
+                self.tasks.unpark_abortable(self.id);
+                other
+            }
+        }
+    }
 }
 
 /// Create a new `Task` to handle a server socket.
@@ -21,12 +352,236 @@ pub trait NewTask: Send + 'static {
 
     /// Create and return a new `Task` value
     fn new_task(&self, stream: TcpStream) -> io::Result<Self::Item>;
+
+    /// Create and return a new `Task` value, given the first bytes read off
+    /// the stream without consuming them.
+    ///
+    /// This is useful for routing a single listener to different handlers
+    /// based on what the client sends first, e.g. sniffing a TLS `ClientHello`
+    /// to decide between a plaintext and a TLS acceptor. The default
+    /// implementation simply ignores `peeked` and forwards to `new_task`.
+    fn new_task_peeked(&self, stream: TcpStream, peeked: &[u8]) -> io::Result<Self::Item> {
+        let _ = peeked;
+        self.new_task(stream)
+    }
+}
+
+/// A builder for configuring and launching a server with `listen`.
+pub struct Listener<T> {
+    new_task: T,
+    peek_bytes: usize,
+    max_connections: Option<usize>,
+    on_error: Option<Box<FnMut(ServerError) + Send>>,
+}
+
+impl<T: NewTask> Listener<T> {
+    /// Create a new `Listener` builder for the given task factory.
+    pub fn new(new_task: T) -> Listener<T> {
+        Listener {
+            new_task: new_task,
+            peek_bytes: 0,
+            max_connections: None,
+            on_error: None,
+        }
+    }
+
+    /// Peek up to `bytes` bytes off each accepted connection before
+    /// dispatching it, handing both the untouched stream and the peeked
+    /// slice to `NewTask::new_task_peeked`.
+    ///
+    /// A short read or EOF while peeking drops that connection without
+    /// affecting the accept loop.
+    pub fn peek(mut self, bytes: usize) -> Listener<T> {
+        self.peek_bytes = bytes;
+        self
+    }
+
+    /// Cap the number of tasks running at once. Once the cap is hit, the
+    /// accept loop stops pulling new connections off the listener (applying
+    /// backpressure at the socket level) until a running task finishes.
+    pub fn max_connections(mut self, max: usize) -> Listener<T> {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Route accept-loop and task errors through `handler` instead of the
+    /// default `error!` logging, so the application can implement metrics,
+    /// circuit-breaking, or retry logic on connection failures.
+    pub fn on_error<F>(mut self, handler: F) -> Listener<T>
+        where F: FnMut(ServerError) + Send + 'static
+    {
+        self.on_error = Some(Box::new(handler));
+        self
+    }
+
+    /// Bind to `addr` and start accepting connections, dispatching them to
+    /// tasks created by the configured `NewTask`.
+    pub fn listen(self, handle: LoopHandle, addr: SocketAddr) -> IoFuture<ServerHandle> {
+        let Listener { new_task, peek_bytes, max_connections, on_error } = self;
+        let new_task = Arc::new(new_task);
+
+        let tasks = Arc::new(TaskTracker::new(max_connections));
+        let errors = Arc::new(ErrorSink::new(on_error));
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+        let listener = handle.clone().tcp_listen(&addr);
+        Box::new(listener.and_then(move |socket| {
+            let addr = try!(socket.local_addr());
+            let handle_tasks = tasks.clone();
+
+            handle.spawn(move |pin| {
+                let pin = pin.clone();
+                let accept_tasks = handle_tasks.clone();
+                let accept_errors = errors.clone();
+                let accept_loop = socket.incoming().for_each(move |(socket, peer)| {
+                    let spawn_pin = pin.clone();
+                    let tasks = accept_tasks.clone();
+                    let errors = accept_errors.clone();
+                    let new_task = new_task.clone();
+
+                    accept_tasks.slot().and_then(move |_| {
+                        if peek_bytes == 0 {
+                            tasks.task_started();
+                            let task = try!(new_task.new_task(socket));
+                            spawn_guarded(&spawn_pin, tasks, errors, Some(peer), task);
+                        } else {
+                            tasks.task_started();
+                            let peek_spawn_pin = spawn_pin.clone();
+                            let peek_tasks = tasks.clone();
+                            let peek_id = tasks.register_abortable();
+                            let peek = AbortablePeek {
+                                inner: Peek::new(socket, peek_bytes),
+                                tasks: tasks.clone(),
+                                id: peek_id,
+                            };
+                            spawn_pin.spawn(peek.then(move |result| {
+                                let maybe_task = match result {
+                                    Ok((stream, buf)) => new_task.new_task_peeked(stream, &buf).ok(),
+                                    Err(_) => None,
+                                };
+
+                                match maybe_task {
+                                    Some(task) => {
+                                        spawn_guarded(&peek_spawn_pin, peek_tasks, errors, Some(peer), task)
+                                    }
+                                    // Peeking or dispatch failed; release the slot we reserved.
+                                    None => peek_tasks.task_finished(),
+                                }
+
+                                Ok(())
+                            }));
+                        }
+                        Ok(())
+                    })
+                }).map_err(move |e| {
+                    errors.report(ServerError::Accept(e));
+                });
+
+                let stopped = stop_rx.then(|_| Ok(()));
+                accept_loop.select(stopped).then(|_| Ok(()))
+            });
+
+            Ok(ServerHandle {
+                local_addr: addr,
+                handle: handle.clone(),
+                stop: Mutex::new(Some(stop_tx)),
+                tasks: tasks,
+            })
+        }))
+    }
+}
+
+/// Spawn an already-counted task, wrapping it so it resolves (and releases
+/// its slot) either on its own completion or when told to abort.
+fn spawn_guarded<F>(pin: &LoopPin,
+                    tasks: Arc<TaskTracker>,
+                    errors: Arc<ErrorSink>,
+                    peer: Option<SocketAddr>,
+                    task: F)
+    where F: Future<Item=(), Error=io::Error> + 'static
+{
+    let id = tasks.register_abortable();
+    let guarded = Abortable { inner: task, tasks: tasks.clone(), id: id };
+    pin.spawn(guarded.map_err(move |e| {
+        errors.report(ServerError::Task { error: e, peer: peer });
+    }).then(move |_| {
+        tasks.task_finished();
+        Ok(())
+    }));
+}
+
+/// A future that peeks up to `peek_bytes` off a `TcpStream` into a reusable
+/// buffer without consuming them, so the bytes are still there for the task
+/// that eventually reads the stream.
+struct Peek {
+    stream: Option<TcpStream>,
+    buf: Box<[u8]>,
+}
+
+impl Peek {
+    fn new(stream: TcpStream, peek_bytes: usize) -> Peek {
+        Peek {
+            stream: Some(stream),
+            buf: vec![0; peek_bytes].into_boxed_slice(),
+        }
+    }
+}
+
+impl Future for Peek {
+    // On success, the stream and the bytes peeked off of it. A short read or
+    // EOF is reported as an error so the caller can drop the connection.
+    type Item = (TcpStream, Box<[u8]>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(TcpStream, Box<[u8]>), io::Error> {
+        // MSG_PEEK always copies from the front of the socket's receive
+        // queue, never from wherever a previous peek left off, so every poll
+        // re-reads the whole buffer from byte 0 instead of accumulating into
+        // it across polls.
+        let n = {
+            let stream = self.stream.as_ref().expect("polled Peek after completion");
+
+            // `peek_raw` goes around the reactor entirely, so it can't itself
+            // register this task for a wakeup. Drive the stream's own
+            // readiness check first -- on `NotReady` that's what schedules
+            // the reactor to poll us again once data shows up -- and only
+            // reach for the raw peek once it reports the socket readable.
+            if let Async::NotReady = stream.poll_read() {
+                return Ok(Async::NotReady);
+            }
+
+            match peek_raw(stream, &mut self.buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                       "eof while peeking connection"));
+        }
+
+        // Dispatch on the first non-empty peek rather than waiting for the
+        // full buffer to fill: a client that sends fewer than `peek_bytes`
+        // and then waits on a reply (a half-duplex handshake) must still be
+        // handed off, just with a shorter-than-requested peeked slice.
+        let stream = self.stream.take().unwrap();
+        let peeked = self.buf[..n].to_vec().into_boxed_slice();
+        Ok((stream, peeked).into())
+    }
 }
 
 /// Spawn a new `Task` that binds to the given `addr` then accepts all incoming
 /// connections; dispatching them to tasks created by `new_task`.
 ///
-/// ```rust,no_run
+/// This crate's `Loop`/`TcpStream` are its own internal compatibility types,
+/// not a separate public dependency, so this example is illustrative rather
+/// than compiled as a doctest.
+///
+/// ```rust,ignore
 /// extern crate futures;
 /// extern crate tokio_proto;
 /// #[macro_use]
@@ -90,27 +645,432 @@ pub fn listen<T>(handle: LoopHandle,
                  new_task: T) -> IoFuture<ServerHandle>
     where T: NewTask
 {
-    let listener = handle.clone().tcp_listen(&addr);
-    listener.and_then(move |socket| {
+    Listener::new(new_task).listen(handle, addr)
+}
+
+/// Create a new `Task` to handle a bound UDP socket.
+pub trait NewDatagramTask: Send + 'static {
+    /// The `Task` value created by this factory
+    type Item: Future<Item=(), Error=io::Error> + 'static;
+
+    /// Create and return a new `Task` value for the bound socket.
+    ///
+    /// Unlike `NewTask`, there is no per-connection accept loop: the
+    /// returned task owns `socket` for as long as the server runs, and is
+    /// responsible for demultiplexing datagrams from different peers
+    /// itself (e.g. by keeping a `SocketAddr`-keyed session map) if the
+    /// protocol needs that.
+    fn new_task(&self, socket: UdpSocket) -> io::Result<Self::Item>;
+
+    /// Create and return a new `Task` value, given the peer address of the
+    /// first datagram waiting on the socket.
+    ///
+    /// The default implementation ignores `peer` and forwards to `new_task`.
+    fn new_task_for_peer(&self, socket: UdpSocket, peer: SocketAddr) -> io::Result<Self::Item> {
+        let _ = peer;
+        self.new_task(socket)
+    }
+}
+
+/// Bind a `UdpSocket` at `addr` and hand it to a `Task` created by
+/// `new_task`, peeking the first datagram's peer address first so the
+/// factory can see who it is before taking ownership of the socket.
+///
+/// Like `listen`, the returned future resolves with a `ServerHandle` as soon
+/// as the socket is bound, regardless of whether a datagram has arrived yet;
+/// the peek itself happens in the background task this spawns.
+pub fn listen_udp<T>(handle: LoopHandle,
+                     addr: SocketAddr,
+                     new_task: T) -> IoFuture<ServerHandle>
+    where T: NewDatagramTask
+{
+    let tasks = Arc::new(TaskTracker::new(None));
+    let errors = Arc::new(ErrorSink::new(None));
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+    let binder = handle.clone().udp_bind(&addr);
+    Box::new(binder.and_then(move |socket| {
         let addr = try!(socket.local_addr());
+        let dispatch_tasks = tasks.clone();
+        let dispatch_errors = errors.clone();
 
-        handle.spawn(|pin| {
+        handle.spawn(move |pin| {
             let pin = pin.clone();
-            socket.incoming().for_each(move |(socket, _)| {
-                let task = try!(new_task.new_task(socket));
-                // TODO: where to punt this error to?
-                pin.spawn(task.map_err(|e| {
-                    error!("task error: {}", e);
-                }));
+            let peek = UdpPeek::new(socket, dispatch_errors.clone());
+            let dispatch = peek.and_then(move |(socket, peer)| {
+                let task = match peer {
+                    Some(peer) => new_task.new_task_for_peer(socket, peer),
+                    None => new_task.new_task(socket),
+                };
+
+                match task {
+                    Ok(task) => {
+                        dispatch_tasks.task_started();
+                        spawn_guarded(&pin, dispatch_tasks.clone(), dispatch_errors.clone(), peer, task);
+                    }
+                    Err(e) => dispatch_errors.report(ServerError::Task { error: e, peer: peer }),
+                }
+
                 Ok(())
-            }).map_err(|e| {
-                // TODO: where to punt this error to?
-                error!("server error: {}", e);
-            })
+            });
+
+            let stopped = stop_rx.then(|_| Ok(()));
+            dispatch.select(stopped).then(|_| Ok(()))
+        });
+
+        Ok(ServerHandle {
+            local_addr: addr,
+            handle: handle.clone(),
+            stop: Mutex::new(Some(stop_tx)),
+            tasks: tasks,
+        })
+    }))
+}
+
+/// A future that peeks the peer address off the first datagram waiting on a
+/// `UdpSocket`, without consuming it, so the task that eventually reads the
+/// socket still sees that datagram.
+///
+/// A real I/O error while peeking is reported through `errors` rather than
+/// silently treated as "no peer found" -- the socket is still handed off to
+/// `new_task` afterward, same as if no datagram had been seen yet.
+struct UdpPeek {
+    socket: Option<UdpSocket>,
+    errors: Arc<ErrorSink>,
+}
+
+impl UdpPeek {
+    fn new(socket: UdpSocket, errors: Arc<ErrorSink>) -> UdpPeek {
+        UdpPeek { socket: Some(socket), errors: errors }
+    }
+}
+
+impl Future for UdpPeek {
+    // The socket, and the peer address of the first datagram waiting on it,
+    // if any was available without blocking.
+    type Item = (UdpSocket, Option<SocketAddr>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(UdpSocket, Option<SocketAddr>), io::Error> {
+        let peer = {
+            let socket = self.socket.as_ref().expect("polled UdpPeek after completion");
+
+            // Same reasoning as Peek::poll: peek_raw_from bypasses the
+            // reactor, so the readiness check has to happen through the
+            // socket's own poll_read first or a NotReady here parks the task
+            // with nothing to ever wake it. This matters most right after
+            // udp_bind, where there's normally no datagram queued yet, so
+            // the very first poll would otherwise hang forever and
+            // listen_udp would never dispatch.
+            if let Async::NotReady = socket.poll_read() {
+                return Ok(Async::NotReady);
+            }
+
+            let mut buf = [0; 64];
+            match peek_raw_from(socket, &mut buf) {
+                Ok((_, peer)) => Some(peer),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.errors.report(ServerError::Task { error: e, peer: None });
+                    None
+                }
+            }
+        };
+
+        let socket = self.socket.take().unwrap();
+        Ok((socket, peer).into())
+    }
+}
+
+/// Create a new per-peer session `Task` for the `listen_udp_sessions`
+/// dispatch model.
+///
+/// Unlike `NewDatagramTask`, where a single task owns the socket and
+/// demultiplexes every peer itself, `listen_udp_sessions` does the
+/// demultiplexing and creates one session the first time each peer is seen.
+/// This is a better fit for protocols like DNS or QUIC that are naturally
+/// structured as one session per client.
+pub trait NewDatagramSession: Send + 'static {
+    /// The `Task` value created by this factory
+    type Item: Future<Item=(), Error=io::Error> + 'static;
+
+    /// Create and return a new `Task` value for a newly-seen peer.
+    fn new_session(&self, socket: SessionSocket, peer: SocketAddr) -> io::Result<Self::Item>;
+}
+
+/// A single peer's side of a `listen_udp_sessions` socket: a `Stream` of
+/// that peer's datagrams, plus a way to queue replies, without the session
+/// task needing to know the real `UdpSocket` is shared with every other peer.
+pub struct SessionSocket {
+    peer: SocketAddr,
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+    outgoing: mpsc::UnboundedSender<(SocketAddr, Vec<u8>)>,
+}
+
+impl SessionSocket {
+    /// The address of the peer this session is talking to.
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Queue `data` to be sent back to this session's peer.
+    ///
+    /// This never blocks the caller; the datagram is handed off to the
+    /// `Router` task that owns the real socket, which writes it out once
+    /// the socket is writable.
+    pub fn send(&self, data: Vec<u8>) {
+        let _ = self.outgoing.send((self.peer, data));
+    }
+}
+
+impl Stream for SessionSocket {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, io::Error> {
+        // An UnboundedReceiver only errors if polled after the sender half
+        // panicked while holding its lock, which never happens here.
+        Ok(self.incoming.poll().unwrap_or(Async::Ready(None)))
+    }
+}
+
+/// Owns the real `UdpSocket` for `listen_udp_sessions`, demultiplexing
+/// incoming datagrams by peer into per-session channels and draining queued
+/// replies back out through the one socket every session shares.
+struct Router<T: NewDatagramSession> {
+    socket: UdpSocket,
+    new_session: Arc<T>,
+    sessions: HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>,
+    outgoing_tx: mpsc::UnboundedSender<(SocketAddr, Vec<u8>)>,
+    outgoing_rx: mpsc::UnboundedReceiver<(SocketAddr, Vec<u8>)>,
+    pin: LoopPin,
+    tasks: Arc<TaskTracker>,
+    errors: Arc<ErrorSink>,
+    buf: Box<[u8]>,
+}
+
+impl<T: NewDatagramSession> Future for Router<T> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        // Flush queued replies before reading more, so a burst of incoming
+        // datagrams can't starve outgoing ones.
+        while let Async::Ready(Some((peer, data))) = self.outgoing_rx.poll().unwrap_or(Async::Ready(None)) {
+            if let Err(e) = self.socket.send_to(&data, &peer) {
+                self.errors.report(ServerError::Task { error: e, peer: Some(peer) });
+            }
+        }
+
+        loop {
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((n, peer)) => {
+                    let data = self.buf[..n].to_vec();
+
+                    if !self.sessions.contains_key(&peer) {
+                        let (tx, rx) = mpsc::unbounded();
+                        let session_socket = SessionSocket {
+                            peer: peer,
+                            incoming: rx,
+                            outgoing: self.outgoing_tx.clone(),
+                        };
+
+                        match self.new_session.new_session(session_socket, peer) {
+                            Ok(task) => {
+                                self.tasks.task_started();
+                                spawn_guarded(&self.pin, self.tasks.clone(), self.errors.clone(), Some(peer), task);
+                                self.sessions.insert(peer, tx);
+                            }
+                            Err(e) => {
+                                self.errors.report(ServerError::Task { error: e, peer: Some(peer) });
+                            }
+                        }
+                    }
+
+                    if let Some(tx) = self.sessions.get(&peer) {
+                        let _ = tx.send(data);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.errors.report(ServerError::Accept(e));
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+/// Bind a `UdpSocket` at `addr` and dispatch one session `Task` per peer,
+/// demultiplexing incoming datagrams by `SocketAddr` instead of handing the
+/// whole socket to a single task as `listen_udp` does.
+///
+/// Like `listen_udp`, the returned future resolves with a `ServerHandle` as
+/// soon as the socket is bound; all demultiplexing happens in the background
+/// `Router` task this spawns.
+pub fn listen_udp_sessions<T>(handle: LoopHandle,
+                              addr: SocketAddr,
+                              new_session: T) -> IoFuture<ServerHandle>
+    where T: NewDatagramSession
+{
+    let new_session = Arc::new(new_session);
+    let tasks = Arc::new(TaskTracker::new(None));
+    let errors = Arc::new(ErrorSink::new(None));
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+    let binder = handle.clone().udp_bind(&addr);
+    Box::new(binder.and_then(move |socket| {
+        let addr = try!(socket.local_addr());
+        let router_tasks = tasks.clone();
+        let router_errors = errors.clone();
+
+        handle.spawn(move |pin| {
+            let pin = pin.clone();
+            let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+            let router = Router {
+                socket: socket,
+                new_session: new_session,
+                sessions: HashMap::new(),
+                outgoing_tx: outgoing_tx,
+                outgoing_rx: outgoing_rx,
+                pin: pin,
+                tasks: router_tasks,
+                errors: router_errors,
+                buf: vec![0; 64 * 1024].into_boxed_slice(),
+            };
+
+            let stopped = stop_rx.then(|_| Ok(()));
+            router.select(stopped).then(|_| Ok(()))
         });
 
-        Ok(ServerHandle { local_addr: addr })
-    }).boxed()
+        Ok(ServerHandle {
+            local_addr: addr,
+            handle: handle.clone(),
+            stop: Mutex::new(Some(stop_tx)),
+            tasks: tasks,
+        })
+    }))
+}
+
+/// Decodes and encodes the message frames of a protocol built on top of a
+/// `TcpStream`'s raw byte stream.
+///
+/// Unlike the `Codec`/`Framed`/`Io` trio from later tokio-core releases, this
+/// crate's `LoopHandle`-era `TcpStream` has no generic framing support built
+/// in, so `Codec` and `Framed` below are this crate's own, built directly on
+/// `Read`/`Write`.
+pub trait Codec: Send + 'static {
+    /// A decoded request frame.
+    type In;
+    /// An encoded response frame.
+    type Out;
+
+    /// Try to decode a frame from the front of `buf`, consuming the bytes it
+    /// used. Returns `Ok(None)` if `buf` doesn't yet hold a whole frame.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Self::In>>;
+
+    /// Append the encoded bytes of `item` to `buf`.
+    fn encode(&mut self, item: Self::Out, buf: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// A `TcpStream` paired with a `Codec`, presenting the connection as a
+/// `Stream` of decoded requests and a `Sink` of responses to encode, instead
+/// of raw bytes.
+pub struct Framed<C: Codec> {
+    stream: TcpStream,
+    codec: C,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+}
+
+impl<C: Codec> Framed<C> {
+    fn new(stream: TcpStream, codec: C) -> Framed<C> {
+        Framed {
+            stream: stream,
+            codec: codec,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+        }
+    }
+}
+
+impl<C: Codec> Stream for Framed<C> {
+    type Item = C::In;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<C::In>, io::Error> {
+        use std::io::Read;
+
+        loop {
+            if let Some(item) = try!(self.codec.decode(&mut self.read_buf)) {
+                return Ok(Async::Ready(Some(item)));
+            }
+
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(Async::Ready(None)),
+                Ok(n) => self.read_buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<C: Codec> futures::sink::Sink for Framed<C> {
+    type SinkItem = C::Out;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: C::Out) -> futures::StartSend<C::Out, io::Error> {
+        try!(self.codec.encode(item, &mut self.write_buf));
+        Ok(futures::AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        use std::io::Write;
+
+        while !self.write_buf.is_empty() {
+            match self.stream.write(&self.write_buf) {
+                Ok(n) => { self.write_buf.drain(..n); }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Create a new `Task` to handle a server socket framed with a `Codec`.
+///
+/// This is a `NewTask`-like factory for protocols built on decoded message
+/// frames instead of raw bytes: implementors get a `Framed` transport (a
+/// `Stream` of decoded requests and a `Sink` of responses to encode) rather
+/// than a `TcpStream`, so they don't need to reimplement buffering.
+pub trait NewFramedTask: Send + 'static {
+    /// The codec used to frame each accepted connection.
+    type Codec: Codec + 'static;
+    /// The `Task` value created by this factory
+    type Item: Future<Item=(), Error=io::Error> + 'static;
+
+    /// Create a new `Codec` instance for a freshly accepted connection.
+    fn new_codec(&self) -> Self::Codec;
+
+    /// Create and return a new `Task` value, given the framed transport.
+    fn new_task(&self, transport: Framed<Self::Codec>) -> io::Result<Self::Item>;
+}
+
+/// Like `listen`, but frames each accepted `TcpStream` with a `Codec`
+/// produced by `new_task` and hands the resulting message `Stream`/`Sink`
+/// to `new_task` instead of the raw stream.
+pub fn framed_listen<T>(handle: LoopHandle,
+                        addr: SocketAddr,
+                        new_task: T) -> IoFuture<ServerHandle>
+    where T: NewFramedTask
+{
+    listen(handle, addr, move |stream: TcpStream| {
+        let transport = Framed::new(stream, new_task.new_codec());
+        new_task.new_task(transport)
+    })
 }
 
 impl ServerHandle {
@@ -118,6 +1078,44 @@ impl ServerHandle {
     pub fn local_addr(&self) -> &SocketAddr {
         &self.local_addr
     }
+
+    /// Stop accepting new connections and return a future that resolves once
+    /// every task currently in flight has completed.
+    pub fn shutdown(&self) -> IoFuture<()> {
+        if let Some(stop) = self.stop.lock().unwrap().take() {
+            let _ = stop.send(());
+        }
+        self.tasks.drain()
+    }
+
+    /// Like `shutdown`, but force any tasks still running after `timeout`
+    /// elapses to resolve (and drop their connection) immediately, rather
+    /// than waiting for them to finish on their own.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> IoFuture<()> {
+        let tasks = self.tasks.clone();
+        let drain = self.shutdown();
+
+        Box::new(self.handle.timeout(timeout).and_then(move |timeout| {
+            drain.select(timeout).then(move |result| {
+                // Either every task finished, or the deadline passed first;
+                // either way, force anything still running -- including
+                // tasks parked waiting on I/O -- to resolve immediately
+                // rather than outlive this call. force_abort only wakes
+                // them, though; it doesn't poll them itself, so wait on a
+                // fresh drain to give them that chance before returning --
+                // otherwise a caller that tears down the reactor right after
+                // this resolves could still race a connection's close.
+                tasks.force_abort();
+                let cleanup = tasks.drain();
+                cleanup.then(move |_| {
+                    match result {
+                        Ok((item, _)) => Ok(item),
+                        Err((e, _)) => Err(e),
+                    }
+                })
+            })
+        }))
+    }
 }
 
 impl<T, U> NewTask for T
@@ -141,3 +1139,298 @@ impl<T, U> NewTask for Take<T>
         self.take()(stream)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{self, SocketAddr};
+    use std::sync::Mutex;
+    use futures::executor;
+
+    struct RecordingNotify {
+        woken: Mutex<bool>,
+    }
+
+    impl executor::Notify for RecordingNotify {
+        fn notify(&self, _id: usize) {
+            *self.woken.lock().unwrap() = true;
+        }
+    }
+
+    fn noop_notify() -> executor::NotifyHandle {
+        struct NoopNotify;
+        impl executor::Notify for NoopNotify {
+            fn notify(&self, _id: usize) {}
+        }
+        executor::NotifyHandle::from(Arc::new(NoopNotify))
+    }
+
+    // A peek must see the bytes a client sent without taking them off the
+    // socket, so the task that eventually does a real read still gets them.
+    #[test]
+    fn peek_raw_does_not_consume_the_socket() {
+        use std::io::{Read, Write};
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        let mut server = listener.accept().unwrap().0;
+
+        client.write_all(b"hello").unwrap();
+
+        let mut peeked = [0; 5];
+        let n = peek_raw(&server, &mut peeked).unwrap();
+        assert_eq!(&peeked[..n], b"hello");
+
+        let mut read = [0; 5];
+        let n = server.read(&mut read).unwrap();
+        assert_eq!(&read[..n], b"hello");
+    }
+
+    // sockaddr_to_addr is what lets listen_udp report a peer's real address;
+    // round-trip it through a real datagram exchange rather than a
+    // hand-built sockaddr_storage.
+    #[test]
+    fn peek_raw_from_reports_the_sender() {
+        let server = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        client.send_to(b"hi", server.local_addr().unwrap()).unwrap();
+
+        let mut buf = [0; 8];
+        let (n, peer) = peek_raw_from(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hi");
+        assert_eq!(peer, client_addr);
+
+        // Still there for a real recv afterward.
+        let (n, _) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hi");
+    }
+
+    // A drained tracker with nothing in flight resolves immediately, and one
+    // with an outstanding task only resolves once that task finishes --
+    // never early, never stuck.
+    #[test]
+    fn drain_resolves_once_every_task_finishes() {
+        let tasks = Arc::new(TaskTracker::new(None));
+        assert_eq!(tasks.drain().poll().unwrap(), Async::Ready(()));
+
+        tasks.task_started();
+        let mut drain = executor::spawn(tasks.drain());
+        let notify = noop_notify();
+        assert_eq!(drain.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+
+        tasks.task_finished();
+        assert_eq!(drain.poll_future_notify(&notify, 0).unwrap(), Async::Ready(()));
+    }
+
+    // Backpressure: once max_connections is hit, a new Slot has to wait, and
+    // it must be released however the in-flight task ends up finishing, not
+    // just on a particular code path.
+    #[test]
+    fn slot_applies_backpressure_and_releases_on_finish() {
+        let tasks = Arc::new(TaskTracker::new(Some(1)));
+
+        assert_eq!(tasks.slot().poll().unwrap(), Async::Ready(()));
+        tasks.task_started();
+
+        let mut slot = executor::spawn(tasks.slot());
+        let notify = noop_notify();
+        assert_eq!(slot.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+
+        tasks.task_finished();
+        assert_eq!(slot.poll_future_notify(&notify, 0).unwrap(), Async::Ready(()));
+    }
+
+    // The bug shutdown_timeout used to have: flipping `abort` alone never
+    // reaches a task parked waiting on its inner future. force_abort has to
+    // actually notify it.
+    #[test]
+    fn force_abort_wakes_a_parked_task() {
+        let tasks = Arc::new(TaskTracker::new(None));
+        let id = tasks.register_abortable();
+        let inner: Box<Future<Item=(), Error=io::Error>> = Box::new(futures::empty());
+        let mut abortable = executor::spawn(Abortable { inner: inner, tasks: tasks.clone(), id: id });
+
+        let notify = Arc::new(RecordingNotify { woken: Mutex::new(false) });
+        let handle = executor::NotifyHandle::from(notify.clone());
+        assert_eq!(abortable.poll_future_notify(&handle, 0).unwrap(), Async::NotReady);
+        assert!(!*notify.woken.lock().unwrap());
+
+        tasks.force_abort();
+        assert!(*notify.woken.lock().unwrap(), "force_abort should wake the parked task");
+
+        assert_eq!(abortable.poll_future_notify(&handle, 0).unwrap(), Async::Ready(()));
+    }
+
+    // force_abort only wakes a parked task; it doesn't poll it itself, so a
+    // drain spawned before the abort only resolves once something actually
+    // gives the woken task another poll (exactly what shutdown_timeout's
+    // post-abort drain does).
+    #[test]
+    fn drain_resolves_after_force_abort_once_the_task_is_repolled() {
+        let tasks = Arc::new(TaskTracker::new(None));
+        tasks.task_started();
+
+        let id = tasks.register_abortable();
+        let inner: Box<Future<Item=(), Error=io::Error>> = Box::new(futures::empty());
+        let mut abortable = executor::spawn(Abortable { inner: inner, tasks: tasks.clone(), id: id });
+        let notify = noop_notify();
+        assert_eq!(abortable.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+
+        let mut drain = executor::spawn(tasks.drain());
+        assert_eq!(drain.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+
+        tasks.force_abort();
+        assert_eq!(drain.poll_future_notify(&notify, 0).unwrap(), Async::NotReady,
+                   "draining shouldn't finish until the aborted task is actually repolled");
+
+        assert_eq!(abortable.poll_future_notify(&notify, 0).unwrap(), Async::Ready(()));
+        tasks.task_finished();
+
+        assert_eq!(drain.poll_future_notify(&notify, 0).unwrap(), Async::Ready(()));
+    }
+
+
+    // End to end: listen_udp must actually dispatch the first datagram to
+    // new_task_for_peer, which only happens if the readiness fix in
+    // UdpPeek::poll is doing its job -- before it, this hung forever.
+    #[test]
+    fn listen_udp_dispatches_first_datagram_to_its_peer() {
+        use tokio_core::Loop;
+        use futures::sync::oneshot;
+
+        struct ReportPeer(Mutex<Option<oneshot::Sender<SocketAddr>>>);
+
+        impl NewDatagramTask for ReportPeer {
+            type Item = futures::Finished<(), io::Error>;
+
+            fn new_task(&self, _socket: UdpSocket) -> io::Result<Self::Item> {
+                Ok(futures::finished(()))
+            }
+
+            fn new_task_for_peer(&self, _socket: UdpSocket, peer: SocketAddr) -> io::Result<Self::Item> {
+                if let Some(tx) = self.0.lock().unwrap().take() {
+                    let _ = tx.send(peer);
+                }
+                Ok(futures::finished(()))
+            }
+        }
+
+        let mut lp = Loop::new().unwrap();
+        let handle = lp.handle();
+
+        let (tx, rx) = oneshot::channel();
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let bound = lp.run(listen_udp(handle, addr, ReportPeer(Mutex::new(Some(tx))))).unwrap();
+        let server_addr = *bound.local_addr();
+
+        let client = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client_addr = client.local_addr().unwrap();
+        client.send_to(b"hi", server_addr).unwrap();
+
+        let peer = lp.run(rx).unwrap();
+        assert_eq!(peer, client_addr);
+    }
+
+    // End to end: a connection task that never finishes on its own (no
+    // timer, no further I/O -- nothing to ever wake it except force_abort)
+    // must still be closed out by shutdown_timeout within the deadline,
+    // not just have shutdown_timeout's own future resolve while the
+    // connection itself leaks on in the background.
+    #[test]
+    fn shutdown_timeout_actually_closes_a_stuck_connection() {
+        use tokio_core::Loop;
+        use std::io::Read;
+
+        struct NeverDone(#[allow(dead_code)] TcpStream);
+
+        impl Future for NeverDone {
+            type Item = ();
+            type Error = io::Error;
+
+            fn poll(&mut self) -> Poll<(), io::Error> {
+                Ok(Async::NotReady)
+            }
+        }
+
+        struct Hang;
+
+        impl NewTask for Hang {
+            type Item = NeverDone;
+
+            fn new_task(&self, stream: TcpStream) -> io::Result<NeverDone> {
+                Ok(NeverDone(stream))
+            }
+        }
+
+        let mut lp = Loop::new().unwrap();
+        let handle = lp.handle();
+
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let bound = lp.run(listen(handle.clone(), addr, Hang)).unwrap();
+        let server_addr = *bound.local_addr();
+
+        let mut client = net::TcpStream::connect(server_addr).unwrap();
+
+        // Give the accept loop a real turn so the connection is actually
+        // dispatched into a running (and registered-abortable) task before
+        // asking for shutdown.
+        lp.run(handle.timeout(Duration::from_millis(50)).and_then(|t| t)).unwrap();
+
+        lp.run(bound.shutdown_timeout(Duration::from_millis(200))).unwrap();
+
+        client.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut buf = [0; 1];
+        let n = client.read(&mut buf).expect("connection should be closed, not leaked");
+        assert_eq!(n, 0);
+    }
+
+    // A connection that's still being peeked (the client connected but
+    // hasn't sent the bytes `.peek()` is waiting for) is counted against the
+    // tracker at accept time, same as a dispatched task, but before this fix
+    // `Peek` had no way to hear about `force_abort` -- only real socket
+    // readiness woke it -- so shutdown_timeout's drain would hang forever
+    // on exactly this connection instead of bounding the wait.
+    #[test]
+    fn shutdown_timeout_does_not_hang_on_a_stuck_peek() {
+        use tokio_core::Loop;
+        use std::io::Read;
+
+        struct Unreachable;
+
+        impl NewTask for Unreachable {
+            type Item = futures::Finished<(), io::Error>;
+
+            fn new_task_peeked(&self, _stream: TcpStream, _peeked: &[u8]) -> io::Result<Self::Item> {
+                panic!("peek should never complete in this test");
+            }
+
+            fn new_task(&self, _stream: TcpStream) -> io::Result<Self::Item> {
+                panic!("peek is configured; new_task shouldn't be called");
+            }
+        }
+
+        let mut lp = Loop::new().unwrap();
+        let handle = lp.handle();
+
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let bound = lp.run(Listener::new(Unreachable).peek(4).listen(handle.clone(), addr)).unwrap();
+        let server_addr = *bound.local_addr();
+
+        // Connect but never send anything, so the peek never has enough
+        // bytes to resolve on its own.
+        let mut client = net::TcpStream::connect(server_addr).unwrap();
+
+        lp.run(handle.timeout(Duration::from_millis(50)).and_then(|t| t)).unwrap();
+
+        lp.run(bound.shutdown_timeout(Duration::from_millis(200)))
+            .expect("shutdown_timeout must not hang on a connection still being peeked");
+
+        client.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut buf = [0; 1];
+        let n = client.read(&mut buf).expect("connection should be closed, not leaked");
+        assert_eq!(n, 0);
+    }
+}